@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::FromRow;
+
+/// A single recorded archive: the content it holds, where it lives in S3,
+/// and where it came from.
+#[derive(FromRow)]
+pub struct ArchiveRecord {
+    pub hash: String,
+    pub size: i64,
+    pub content_type: Option<String>,
+    pub source_url: String,
+    pub fetched_at: DateTime<Utc>,
+    pub public: bool,
+    pub s3_key: String,
+}
+
+#[derive(Clone)]
+pub struct DbConn {
+    pool: PgPool,
+}
+
+impl DbConn {
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+
+        Ok(DbConn { pool })
+    }
+
+    pub async fn migrate(&self) -> Result<(), sqlx::migrate::MigrateError> {
+        sqlx::migrate!("./migrations").run(&self.pool).await
+    }
+
+    /// Begins a transaction holding a transaction-scoped advisory lock keyed
+    /// on `hash`, so two concurrent first-time archivals of the same new
+    /// content serialize instead of racing: both would otherwise pass
+    /// `find_by_hash` before either has inserted, and could PUT the same key
+    /// with two different ACLs, leaving the surviving `archives` row (the
+    /// other is dropped by `ON CONFLICT ... DO NOTHING`) recording a
+    /// visibility that doesn't match whichever PUT's ACL actually landed
+    /// last in S3. The lock is released on `commit` (or on drop, via
+    /// rollback).
+    pub async fn lock_hash(&self, hash: &str) -> Result<HashLock<'_>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+
+        Ok(HashLock { tx })
+    }
+}
+
+/// A held per-hash advisory lock, scoped to the transaction it was taken in.
+/// `find_by_hash`/`insert_archive` run inside that same transaction so the
+/// dedup check and the row insert are atomic with respect to any other
+/// holder of the same hash's lock.
+pub struct HashLock<'c> {
+    tx: sqlx::Transaction<'c, sqlx::Postgres>,
+}
+
+impl<'c> HashLock<'c> {
+    /// Looks up an archive by content hash so identical content can be
+    /// served from its existing location instead of being re-uploaded.
+    pub async fn find_by_hash(&mut self, hash: &str) -> Result<Option<ArchiveRecord>, sqlx::Error> {
+        sqlx::query_as::<_, ArchiveRecord>(
+            "SELECT hash, size, content_type, source_url, fetched_at, public, s3_key \
+             FROM archives WHERE hash = $1",
+        )
+        .bind(hash)
+        .fetch_optional(&mut *self.tx)
+        .await
+    }
+
+    pub async fn insert_archive(&mut self, record: &ArchiveRecord) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO archives (hash, size, content_type, source_url, fetched_at, public, s3_key) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7) \
+             ON CONFLICT (hash) DO NOTHING",
+        )
+        .bind(&record.hash)
+        .bind(record.size)
+        .bind(&record.content_type)
+        .bind(&record.source_url)
+        .bind(record.fetched_at)
+        .bind(record.public)
+        .bind(&record.s3_key)
+        .execute(&mut *self.tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Commits the transaction, releasing the advisory lock.
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.commit().await
+    }
+}
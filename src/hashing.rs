@@ -0,0 +1,31 @@
+use std::io;
+
+use bytes::Bytes;
+use rocket::futures::{Stream, StreamExt};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+/// Spools a byte stream to a temporary file, returning it alongside the
+/// number of bytes written.
+///
+/// This keeps memory use bounded regardless of source size — the caller
+/// can then hash and re-stream the spooled file via `upload::hash_file`
+/// and `upload::stream_file`, the same two-pass pattern `/upload` uses for
+/// its own `TempFile` uploads.
+pub async fn spool_to_temp_file<S>(mut stream: S) -> io::Result<(NamedTempFile, u64)>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    let temp = NamedTempFile::new()?;
+    let mut file = tokio::fs::File::create(temp.path()).await?;
+    let mut size: u64 = 0;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    Ok((temp, size))
+}
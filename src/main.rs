@@ -2,17 +2,21 @@ use std::collections::HashMap;
 use std::env;
 use std::io;
 
+use rocket::data::{Data, ToByteUnit};
 use rocket::figment::providers::Env;
+use rocket::form::Form;
+use rocket::fs::TempFile;
 use rocket::futures::TryStreamExt;
 use rocket::{Error, State};
 use rocket::request::{FromRequest, Request, Outcome};
-use rocket::serde::{Serialize, Deserialize, json::Json};
-use rocket::http::Status;
+use rocket::serde::{Serialize, Deserialize, json::{Json, serde_json}};
+use rocket::http::{ContentType, Status};
 use rocket::fairing::AdHoc;
 use rocket::outcome::try_outcome;
 
-use rusoto_core::Region;
-use rusoto_s3::{S3Client, S3, PutObjectRequest, StreamingBody};
+use rusoto_core::{HttpClient, Region};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::{ListObjectsV2Request, S3, S3Client};
 
 use reqwest::header::CONTENT_TYPE;
 
@@ -22,6 +26,21 @@ use url::Url;
 
 #[macro_use] extern crate rocket;
 
+mod auth;
+mod db;
+mod hashing;
+mod sigv4;
+mod storage;
+mod upload;
+mod util;
+
+use auth::{hash_payload, verify_payload, AuthContext, AuthSource, HawkAuth, HawkCredential, StaticTokenAuth};
+use db::{ArchiveRecord, DbConn};
+use hashing::spool_to_temp_file;
+use sigv4::{presign_get, SigningCredentials};
+use storage::{put_object_streaming, PutArgs, PutError};
+use upload::{hash_file, hash_file_as_hawk_payload, stream_file};
+
 #[get("/")]
 fn index() -> &'static str {
     "Hello, world!"
@@ -32,35 +51,30 @@ struct ArchiveRequest {
     pub source: String,
     pub suffix: String,
     pub public: bool,
+    /// Lifetime in seconds of the presigned GET URL, when `public` is false.
+    #[serde(default = "default_expires_in")]
+    pub expires_in: u32,
 }
 
+fn default_expires_in() -> u32 { 3600 }
+
 #[derive(Serialize)]
 struct ArchiveResult {
     pub location: String,
 }
 
-struct BearerToken(pub String);
+struct Authenticated(pub AuthContext);
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for BearerToken {
+impl<'r> FromRequest<'r> for Authenticated {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let st = try_outcome!(req.guard::<&'r State<CommonState>>().await);
 
-        fn is_valid<'a>(token: &str, valid: &str) -> bool {
-            let token = match token.strip_prefix("Bearer ") {
-                Some(x) => x,
-                None => return false,
-            };
-
-            token == valid
-        }
-
-        match req.headers().get_one("authorization") {
-            None => Outcome::Failure((Status::BadRequest, ())),
-            Some(token) if is_valid(token, &st.bearer_token) => Outcome::Success(BearerToken(token.into())),
-            Some(_) => Outcome::Failure((Status::BadRequest, ())),
+        match st.auth.authenticate(req).await {
+            Ok(ctx) => Outcome::Success(Authenticated(ctx)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ())),
         }
     }
 }
@@ -86,6 +100,11 @@ enum ArchiveError {
     ContentFetchFailed,
     ContentUploadFailed,
     InvalidConfiguration,
+    DatabaseError,
+    ListFailed,
+    ContentLengthMismatch,
+    MalformedRequest,
+    Unauthorized,
 }
 
 #[derive(Serialize)]
@@ -93,8 +112,26 @@ struct ErrorInfo {
     error: ArchiveError,
 }
 
-#[post("/archive", data = "<request>")]
-async fn archive(token: BearerToken, request: Json<ArchiveRequest>, s: &State<CommonState>) -> Result<Json<ArchiveResult>, ArchiveFailure> {
+/// The request body is read as raw bytes (rather than taken as a `Json<T>`
+/// data guard) so its real payload hash can be checked against what the
+/// `Authenticated` guard's auth scheme claimed before any of it is trusted.
+#[post("/archive", data = "<body>")]
+async fn archive(auth: Authenticated, content_type: &ContentType, body: Data<'_>, s: &State<CommonState>) -> Result<Json<ArchiveResult>, ArchiveFailure> {
+    let bytes = match body.open(1.mebibytes()).into_bytes().await {
+        Ok(b) if b.is_complete() => b.into_inner(),
+        _ => return Err(ArchiveError::MalformedRequest.into()),
+    };
+
+    let computed_hash = hash_payload(&content_type.to_string(), &bytes);
+    if verify_payload(&auth.0, &computed_hash).is_err() {
+        return Err(ArchiveError::Unauthorized.into());
+    }
+
+    let request: ArchiveRequest = match serde_json::from_slice(&bytes) {
+        Ok(r) => r,
+        Err(_) => return Err(ArchiveError::MalformedRequest.into()),
+    };
+
     let resp = match reqwest::get(&request.source).await {
         Ok(r) => r,
         Err(e) => return Err(ArchiveError::ContentFetchFailed.into()),
@@ -104,7 +141,6 @@ async fn archive(token: BearerToken, request: Json<ArchiveRequest>, s: &State<Co
         return Err(ArchiveError::ContentFetchFailed.into());
     }
 
-    let content_length = resp.content_length();
     let content_type = match resp.headers().get(CONTENT_TYPE).map(|t| t.to_str())  {
         Some(Ok(s)) => Some(s.into()),
         Some(Err(_)) => None,
@@ -112,50 +148,276 @@ async fn archive(token: BearerToken, request: Json<ArchiveRequest>, s: &State<Co
     };
 
     let stream = resp.bytes_stream().map_err(|e| io::Error::new(io::ErrorKind::Other, e));
-    let body = StreamingBody::new(stream);
-
-    let mut put = PutObjectRequest::default();
-    put.bucket = s.bucket_name.to_string();
-    put.key =  request.0.suffix.to_string();
-    put.body = Some(body);
-    put.acl = Some("public-read".into());
-    put.content_length = content_length.map(|l| l as i64);
-    put.content_type = content_type;
-    put.cache_control = Some("private, max-age=604800".into());
-    put.metadata = Some(HashMap::from([
-        ("source".into(), request.0.source.into()),
-        ("fetched-at".into(), Utc::now().to_rfc3339())
-    ]));
-
-    let _ = match s.client.put_object(put).await {
+    let (temp, size) = match spool_to_temp_file(stream).await {
         Ok(r) => r,
-        Err(_) => return Err(ArchiveError::ContentUploadFailed.into()),
+        Err(_) => return Err(ArchiveError::ContentFetchFailed.into()),
+    };
+    let size = size as i64;
+
+    let hash = match hash_file(temp.path()).await {
+        Ok(h) => h,
+        Err(_) => return Err(ArchiveError::ContentFetchFailed.into()),
+    };
+
+    let key = format!("sha256/{}", hash);
+
+    let mut lock = s.db.lock_hash(&hash).await.map_err(|_| ArchiveError::DatabaseError)?;
+
+    if let Some(existing) = lock.find_by_hash(&hash).await.map_err(|_| ArchiveError::DatabaseError)? {
+        lock.commit().await.map_err(|_| ArchiveError::DatabaseError)?;
+        return Ok(ArchiveResult {
+            location: build_location(s, &existing.s3_key, existing.public, request.expires_in)?.into(),
+        }.into());
+    }
+
+    let put_args = PutArgs {
+        bucket: s.bucket_name.to_string(),
+        key: key.clone(),
+        acl: if request.public { "public-read".into() } else { "private".into() },
+        content_type: content_type.clone(),
+        content_length: Some(size),
+        cache_control: Some("private, max-age=604800".into()),
+        metadata: HashMap::from([
+            ("source".into(), request.source.clone()),
+            ("fetched-at".into(), Utc::now().to_rfc3339())
+        ]),
+    };
+
+    let put_stream = match stream_file(temp.path()).await {
+        Ok(s) => s,
+        Err(_) => return Err(ArchiveError::ContentFetchFailed.into()),
+    };
+    match put_object_streaming(&s.client, put_args, put_stream).await {
+        Ok(()) => (),
+        Err(PutError::Fetch) => return Err(ArchiveError::ContentFetchFailed.into()),
+        Err(PutError::Upload) => return Err(ArchiveError::ContentUploadFailed.into()),
     };
 
-    let suffix = format!("/{}/{}", s.bucket_name, request.0.suffix.as_str());
-    let url = match s.public_url.join(&suffix) {
-        Ok(u) => u,
-        Err(_) => return Err(ArchiveError::InvalidConfiguration.into()),
+    let record = ArchiveRecord {
+        hash,
+        size,
+        content_type,
+        source_url: request.source.clone(),
+        fetched_at: Utc::now(),
+        public: request.public,
+        s3_key: key.clone(),
     };
+    lock.insert_archive(&record).await.map_err(|_| ArchiveError::DatabaseError)?;
+    lock.commit().await.map_err(|_| ArchiveError::DatabaseError)?;
 
     Ok(ArchiveResult {
-        location: url.into(),
+        location: build_location(s, &key, request.public, request.expires_in)?.into(),
     }.into())
 }
 
+#[derive(Serialize)]
+struct ArchiveObject {
+    pub key: String,
+    pub size: i64,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ArchiveListing {
+    pub objects: Vec<ArchiveObject>,
+    pub next_cursor: Option<String>,
+}
+
+/// Lists stored objects, mirroring S3's `ListObjectsV2` continuation-token
+/// model: pass the returned `next_cursor` back as `cursor` to resume where
+/// the previous page left off.
+#[get("/archives?<prefix>&<max_keys>&<cursor>")]
+async fn list_archives(
+    _auth: Authenticated,
+    prefix: Option<String>,
+    max_keys: Option<i64>,
+    cursor: Option<String>,
+    s: &State<CommonState>,
+) -> Result<Json<ArchiveListing>, ArchiveFailure> {
+    let mut list = ListObjectsV2Request::default();
+    list.bucket = s.bucket_name.to_string();
+    list.prefix = prefix;
+    list.max_keys = max_keys;
+    list.continuation_token = cursor;
+
+    let resp = match s.client.list_objects_v2(list).await {
+        Ok(r) => r,
+        Err(_) => return Err(ArchiveError::ListFailed.into()),
+    };
+
+    let objects = resp.contents.unwrap_or_default().into_iter().map(|o| ArchiveObject {
+        key: o.key.unwrap_or_default(),
+        size: o.size.unwrap_or(0),
+        last_modified: o.last_modified,
+    }).collect();
+
+    let next_cursor = match resp.is_truncated {
+        Some(true) => resp.next_continuation_token,
+        _ => None,
+    };
+
+    Ok(ArchiveListing { objects, next_cursor }.into())
+}
+
+#[derive(FromForm)]
+struct UploadForm<'r> {
+    pub suffix: String,
+    pub content_type: Option<String>,
+    #[field(default = true)]
+    pub public: bool,
+    #[field(default = 3600)]
+    pub expires_in: u32,
+    pub content_length: Option<i64>,
+    pub file: TempFile<'r>,
+}
+
+/// Accepts a `multipart/form-data` upload and archives the attached `file`
+/// field through the same content-addressed put/multipart path as
+/// `/archive`, for callers that already have the bytes in hand instead of
+/// a fetchable `source` URL.
+#[post("/upload", data = "<form>")]
+async fn upload(auth: Authenticated, form: Form<UploadForm<'_>>, s: &State<CommonState>) -> Result<Json<ArchiveResult>, ArchiveFailure> {
+    let path = match form.file.path() {
+        Some(p) => p,
+        None => return Err(ArchiveError::ContentFetchFailed.into()),
+    };
+
+    let actual_length = form.file.len() as i64;
+    if let Some(declared) = form.content_length {
+        if declared != actual_length {
+            return Err(ArchiveError::ContentLengthMismatch.into());
+        }
+    }
+
+    // The file field is the payload that matters here, so it's what gets
+    // hashed and checked against the auth scheme's claimed payload hash —
+    // streamed off disk rather than loaded into memory, to keep large
+    // uploads bounded the same way `storage::put_object_streaming` does.
+    //
+    // This is a server-specific extension to Hawk, not the spec's literal
+    // body hash — see the doc comment on `hash_file_as_hawk_payload` for why
+    // and what signing clients need to match.
+    let upload_content_type = form.content_type.clone().unwrap_or_else(|| "application/octet-stream".into());
+    let computed_hash = hash_file_as_hawk_payload(path, &upload_content_type).await.map_err(|_| ArchiveError::ContentFetchFailed)?;
+    if verify_payload(&auth.0, &computed_hash).is_err() {
+        return Err(ArchiveError::Unauthorized.into());
+    }
+
+    let hash = hash_file(path).await.map_err(|_| ArchiveError::ContentFetchFailed)?;
+    let key = format!("sha256/{}", hash);
+
+    let mut lock = s.db.lock_hash(&hash).await.map_err(|_| ArchiveError::DatabaseError)?;
+
+    if let Some(existing) = lock.find_by_hash(&hash).await.map_err(|_| ArchiveError::DatabaseError)? {
+        lock.commit().await.map_err(|_| ArchiveError::DatabaseError)?;
+        return Ok(ArchiveResult {
+            location: build_location(s, &existing.s3_key, existing.public, form.expires_in)?.into(),
+        }.into());
+    }
+
+    let stream = stream_file(path).await.map_err(|_| ArchiveError::ContentFetchFailed)?;
+    let put_args = PutArgs {
+        bucket: s.bucket_name.to_string(),
+        key: key.clone(),
+        acl: if form.public { "public-read".into() } else { "private".into() },
+        content_type: form.content_type.clone(),
+        content_length: Some(actual_length),
+        cache_control: Some("private, max-age=604800".into()),
+        metadata: HashMap::from([
+            ("fetched-at".into(), Utc::now().to_rfc3339())
+        ]),
+    };
+
+    match put_object_streaming(&s.client, put_args, stream).await {
+        Ok(()) => (),
+        Err(PutError::Fetch) => return Err(ArchiveError::ContentFetchFailed.into()),
+        Err(PutError::Upload) => return Err(ArchiveError::ContentUploadFailed.into()),
+    };
+
+    let record = ArchiveRecord {
+        hash,
+        size: actual_length,
+        content_type: form.content_type.clone(),
+        source_url: format!("upload:{}", form.suffix),
+        fetched_at: Utc::now(),
+        public: form.public,
+        s3_key: key.clone(),
+    };
+    lock.insert_archive(&record).await.map_err(|_| ArchiveError::DatabaseError)?;
+    lock.commit().await.map_err(|_| ArchiveError::DatabaseError)?;
+
+    Ok(ArchiveResult {
+        location: build_location(s, &key, form.public, form.expires_in)?.into(),
+    }.into())
+}
+
+/// Builds the URL a caller should use to fetch `key`: a plain public URL
+/// when the object was stored with a public ACL, or a SigV4 presigned GET
+/// URL (valid for `expires_in` seconds) when it was stored privately.
+fn build_location(s: &CommonState, key: &str, public: bool, expires_in: u32) -> Result<Url, ArchiveError> {
+    if public {
+        let suffix = format!("/{}/{}", s.bucket_name, key);
+        return s.public_url.join(&suffix).map_err(|_| ArchiveError::InvalidConfiguration);
+    }
+
+    presign_get(&s.endpoint_url, &s.signing_credentials, &s.bucket_name, key, expires_in, Utc::now())
+        .map_err(|_| ArchiveError::InvalidConfiguration)
+}
+
 #[derive(Deserialize)]
 struct Config {
     pub bucket_name: String,
-    pub bearer_token: String,
     pub public_url: String,
     pub endpoint: String,
+    pub database_url: String,
+    /// Either "static" or "hawk"; selects which `AuthSource` is installed.
+    #[serde(default = "default_auth_scheme")]
+    pub auth_scheme: String,
+    /// Shared secret for the `static` scheme.
+    pub bearer_token: Option<String>,
+    /// JSON object of `{ "key id": "key" }` for the `hawk` scheme.
+    pub hawk_credentials: Option<String>,
+    #[serde(default = "default_hawk_port")]
+    pub hawk_default_port: u16,
+    #[serde(default = "default_hawk_skew")]
+    pub hawk_timestamp_skew_secs: i64,
+    pub aws_access_key_id: String,
+    pub aws_secret_access_key: String,
+    #[serde(default = "default_aws_region")]
+    pub aws_region: String,
 }
 
+fn default_auth_scheme() -> String { "static".into() }
+fn default_hawk_port() -> u16 { 443 }
+fn default_hawk_skew() -> i64 { 60 }
+fn default_aws_region() -> String { "us-east-1".into() }
+
 struct CommonState {
     pub client: S3Client,
     pub bucket_name: String,
-    pub bearer_token: String,
     pub public_url: Url,
+    pub endpoint_url: Url,
+    pub signing_credentials: SigningCredentials,
+    pub db: DbConn,
+    pub auth: Box<dyn AuthSource>,
+}
+
+fn build_auth_source(config: &Config) -> Result<Box<dyn AuthSource>, String> {
+    match config.auth_scheme.as_str() {
+        "static" => {
+            let token = config.bearer_token.clone().ok_or("bearer_token is required for the static auth scheme")?;
+            Ok(Box::new(StaticTokenAuth { token }))
+        },
+        "hawk" => {
+            let raw = config.hawk_credentials.as_deref().ok_or("hawk_credentials is required for the hawk auth scheme")?;
+            let keys: HashMap<String, String> = rocket::serde::json::serde_json::from_str(raw)
+                .map_err(|e| format!("invalid hawk_credentials: {}", e))?;
+            let credentials = keys.into_iter().map(|(id, key)| (id, HawkCredential { key })).collect();
+
+            Ok(Box::new(HawkAuth::new(credentials, config.hawk_default_port, config.hawk_timestamp_skew_secs)))
+        },
+        other => Err(format!("unknown auth_scheme: {}", other)),
+    }
 }
 
 #[rocket::main]
@@ -172,20 +434,64 @@ async fn main() -> Result<(), rocket::Error> {
                     return Err(rocket)
                 },
             };
-            let region = Region::Custom { name: "ceph".into(), endpoint: config.endpoint };
-            let client = S3Client::new(region);
+            let endpoint_url = match Url::parse(&config.endpoint) {
+                Ok(u) => u,
+                Err(_) => return Err(rocket),
+            };
+            let region = Region::Custom { name: "ceph".into(), endpoint: config.endpoint.clone() };
+            let signing_credentials = SigningCredentials {
+                access_key_id: config.aws_access_key_id.clone(),
+                secret_access_key: config.aws_secret_access_key.clone(),
+                region: config.aws_region.clone(),
+            };
+            // The S3 client authenticates its own PUT/LIST/multipart calls
+            // with this same static credential pair, so they can never drift
+            // from the credentials `sigv4::presign_get` signs GET URLs with.
+            let credentials_provider = StaticProvider::new_minimal(
+                signing_credentials.access_key_id.clone(),
+                signing_credentials.secret_access_key.clone(),
+            );
+            let dispatcher = match HttpClient::new() {
+                Ok(d) => d,
+                Err(_) => return Err(rocket),
+            };
+            let client = S3Client::new_with(dispatcher, credentials_provider, region);
             let public_url = match Url::parse(&config.public_url) {
                 Ok(u) => u,
                 Err(_) => return Err(rocket),
             };
+
+            let db = match DbConn::connect(&config.database_url).await {
+                Ok(db) => db,
+                Err(e) => {
+                    error!("failed to connect to database: {}", e);
+                    return Err(rocket)
+                },
+            };
+            if let Err(e) = db.migrate().await {
+                error!("failed to run database migrations: {}", e);
+                return Err(rocket)
+            }
+
+            let auth = match build_auth_source(&config) {
+                Ok(auth) => auth,
+                Err(e) => {
+                    error!("failed to configure auth source: {}", e);
+                    return Err(rocket)
+                },
+            };
+
             Ok(rocket.manage(CommonState {
                 client: client,
                 bucket_name: config.bucket_name,
-                bearer_token: config.bearer_token,
                 public_url: public_url,
+                endpoint_url: endpoint_url,
+                signing_credentials: signing_credentials,
+                db: db,
+                auth: auth,
             }))
         }))
-        .mount("/", routes![index, archive])
+        .mount("/", routes![index, archive, list_archives, upload])
         .ignite().await?
         .launch().await
 
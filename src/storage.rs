@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use rocket::futures::{Stream, StreamExt};
+
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, PutObjectRequest, S3Client, StreamingBody,
+    UploadPartRequest, S3,
+};
+
+/// S3 requires every part but the last to be at least 5 MiB.
+const MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Below this declared size we fall back to a single PUT; at or above it, or
+/// when the content length is unknown, the upload goes multipart.
+const MULTIPART_THRESHOLD: usize = 16 * 1024 * 1024;
+
+pub struct PutArgs {
+    pub bucket: String,
+    pub key: String,
+    pub acl: String,
+    pub content_type: Option<String>,
+    pub content_length: Option<i64>,
+    pub cache_control: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum PutError {
+    Fetch,
+    Upload,
+}
+
+/// Uploads a byte stream to S3, choosing between a single `PutObject` and a
+/// multipart upload depending on the declared content length.
+///
+/// Streams at or above `MULTIPART_THRESHOLD`, or whose length isn't known up
+/// front, are buffered into >=5 MiB parts and sent via `UploadPart`. If any
+/// part fails (including the source stream erroring out from under us) the
+/// in-progress upload is aborted so no orphaned parts are left accruing
+/// storage.
+pub async fn put_object_streaming<S>(
+    client: &S3Client,
+    args: PutArgs,
+    stream: S,
+) -> Result<(), PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin + Send + 'static,
+{
+    let use_multipart = match args.content_length {
+        Some(len) => len as usize >= MULTIPART_THRESHOLD,
+        None => true,
+    };
+
+    if use_multipart {
+        put_multipart(client, args, stream).await
+    } else {
+        put_single(client, args, stream).await
+    }
+}
+
+async fn put_single<S>(client: &S3Client, args: PutArgs, stream: S) -> Result<(), PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin + Send + 'static,
+{
+    let mut put = PutObjectRequest::default();
+    put.bucket = args.bucket;
+    put.key = args.key;
+    put.body = Some(StreamingBody::new(stream));
+    put.acl = Some(args.acl);
+    put.content_length = args.content_length;
+    put.content_type = args.content_type;
+    put.cache_control = args.cache_control;
+    put.metadata = Some(args.metadata);
+
+    client.put_object(put).await.map_err(|_| PutError::Upload)?;
+
+    Ok(())
+}
+
+async fn put_multipart<S>(
+    client: &S3Client,
+    args: PutArgs,
+    mut stream: S,
+) -> Result<(), PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin + Send + 'static,
+{
+    let mut create = CreateMultipartUploadRequest::default();
+    create.bucket = args.bucket.clone();
+    create.key = args.key.clone();
+    create.acl = Some(args.acl);
+    create.content_type = args.content_type;
+    create.cache_control = args.cache_control;
+    create.metadata = Some(args.metadata);
+
+    let bucket = args.bucket.clone();
+    let key = args.key.clone();
+
+    let created = client
+        .create_multipart_upload(create)
+        .await
+        .map_err(|_| PutError::Upload)?;
+    let upload_id = created.upload_id.ok_or(PutError::Upload)?;
+
+    let result = finish_multipart(client, &bucket, &key, &upload_id, &mut stream).await;
+
+    if result.is_err() {
+        let mut abort = AbortMultipartUploadRequest::default();
+        abort.bucket = bucket;
+        abort.key = key;
+        abort.upload_id = upload_id;
+        let _ = client.abort_multipart_upload(abort).await;
+    }
+
+    result
+}
+
+/// Drives the part upload loop and completes the upload as one unit, so
+/// `put_multipart` can route every failure mode — a failed part, a failed
+/// `complete_multipart_upload` call, and the zero-parts case an empty
+/// source stream produces (which S3 rejects) — through the same abort path.
+async fn finish_multipart<S>(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    stream: &mut S,
+) -> Result<(), PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    let parts = drive_multipart(client, bucket, key, upload_id, stream).await?;
+    if parts.is_empty() {
+        return Err(PutError::Upload);
+    }
+
+    let mut complete = CompleteMultipartUploadRequest::default();
+    complete.bucket = bucket.to_string();
+    complete.key = key.to_string();
+    complete.upload_id = upload_id.to_string();
+    complete.multipart_upload = Some(CompletedMultipartUpload {
+        parts: Some(parts),
+    });
+
+    client
+        .complete_multipart_upload(complete)
+        .await
+        .map_err(|_| PutError::Upload)?;
+
+    Ok(())
+}
+
+/// Pulls chunks off `stream` until it has accumulated a full
+/// `MULTIPART_PART_SIZE` part, returning `None` once the stream is
+/// exhausted and nothing is left buffered. The final part may be smaller
+/// than `MULTIPART_PART_SIZE` — S3 only requires every part but the last to
+/// meet the minimum.
+///
+/// Pure with respect to S3: it only pulls from `stream`, so it can be
+/// exercised directly in tests without a real or mocked `S3Client`.
+async fn next_part<S>(stream: &mut S, buf: &mut BytesMut) -> Result<Option<Bytes>, PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    let mut exhausted = false;
+
+    while buf.len() < MULTIPART_PART_SIZE {
+        match stream.next().await {
+            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+            Some(Err(_)) => return Err(PutError::Fetch),
+            None => {
+                exhausted = true;
+                break;
+            }
+        }
+    }
+
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    let take = if exhausted { buf.len() } else { MULTIPART_PART_SIZE };
+    Ok(Some(buf.split_to(take.min(buf.len())).freeze()))
+}
+
+async fn drive_multipart<S>(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    stream: &mut S,
+) -> Result<Vec<CompletedPart>, PutError>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    let mut parts = Vec::new();
+    let mut buf = BytesMut::new();
+    let mut part_number = 1;
+
+    while let Some(part_bytes) = next_part(stream, &mut buf).await? {
+        let part_len = part_bytes.len() as i64;
+
+        let mut upload_part = UploadPartRequest::default();
+        upload_part.bucket = bucket.to_string();
+        upload_part.key = key.to_string();
+        upload_part.upload_id = upload_id.to_string();
+        upload_part.part_number = part_number;
+        upload_part.content_length = Some(part_len);
+        upload_part.body = Some(StreamingBody::from(part_bytes.to_vec()));
+
+        let uploaded = client
+            .upload_part(upload_part)
+            .await
+            .map_err(|_| PutError::Upload)?;
+        let e_tag = uploaded.e_tag.ok_or(PutError::Upload)?;
+
+        parts.push(CompletedPart {
+            e_tag: Some(e_tag),
+            part_number: Some(part_number),
+        });
+
+        part_number += 1;
+    }
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::futures::stream;
+
+    fn ok_stream(chunks: Vec<&[u8]>) -> impl Stream<Item = Result<Bytes, io::Error>> + Unpin {
+        stream::iter(chunks.into_iter().map(|c| Ok(Bytes::from(c.to_vec()))))
+    }
+
+    #[rocket::async_test]
+    async fn single_chunk_below_part_size_is_the_only_part() {
+        let mut s = ok_stream(vec![b"hello world"]);
+        let mut buf = BytesMut::new();
+
+        let part = next_part(&mut s, &mut buf).await.unwrap();
+        assert_eq!(part.as_deref(), Some(&b"hello world"[..]));
+
+        let end = next_part(&mut s, &mut buf).await.unwrap();
+        assert!(end.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn exact_part_size_boundary_splits_cleanly() {
+        let first = vec![b'a'; MULTIPART_PART_SIZE];
+        let second = b"overflow".to_vec();
+        let mut s = ok_stream(vec![&first, &second]);
+        let mut buf = BytesMut::new();
+
+        let part1 = next_part(&mut s, &mut buf).await.unwrap().unwrap();
+        assert_eq!(part1.len(), MULTIPART_PART_SIZE);
+
+        let part2 = next_part(&mut s, &mut buf).await.unwrap().unwrap();
+        assert_eq!(&part2[..], b"overflow");
+
+        assert!(next_part(&mut s, &mut buf).await.unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn chunk_straddling_the_boundary_is_split_across_two_parts() {
+        // One chunk that's 10 bytes past the part-size boundary: the first
+        // MULTIPART_PART_SIZE bytes form a full part, the remaining 10 carry
+        // over into the next.
+        let mut data = vec![b'x'; MULTIPART_PART_SIZE];
+        data.extend_from_slice(b"0123456789");
+        let mut s = ok_stream(vec![&data]);
+        let mut buf = BytesMut::new();
+
+        let part1 = next_part(&mut s, &mut buf).await.unwrap().unwrap();
+        assert_eq!(part1.len(), MULTIPART_PART_SIZE);
+
+        let part2 = next_part(&mut s, &mut buf).await.unwrap().unwrap();
+        assert_eq!(&part2[..], b"0123456789");
+
+        assert!(next_part(&mut s, &mut buf).await.unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn empty_stream_yields_no_parts() {
+        let mut s = ok_stream(vec![]);
+        let mut buf = BytesMut::new();
+
+        assert!(next_part(&mut s, &mut buf).await.unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn stream_error_propagates_as_fetch_failure() {
+        let mut s = stream::iter(vec![Err(io::Error::new(io::ErrorKind::Other, "boom"))]);
+        let mut buf = BytesMut::new();
+
+        match next_part(&mut s, &mut buf).await {
+            Err(PutError::Fetch) => (),
+            other => panic!("expected PutError::Fetch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::io;
+use std::path::Path;
+
+use rocket::futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+
+use crate::auth::PayloadHasher;
+use crate::util::to_hex;
+
+/// Reads `path` once up front to compute its SHA-256 digest, so the caller
+/// can content-address the upload the same way `/archive` does.
+pub async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Computes the Hawk payload hash of `path`'s contents without loading the
+/// whole file into memory, for verifying the `Authorization` header's
+/// claimed hash against what was actually uploaded.
+///
+/// Deliberate deviation from stock Hawk: the spec hashes the literal request
+/// body, but by the time a `Form<UploadForm>` data guard hands us a `path`
+/// the multipart envelope is already gone. Signing clients talking to
+/// `/upload` must compute `hash` over the decoded `file` field's bytes plus
+/// this declared content type instead of the raw `multipart/form-data` body
+/// — `/archive`, which reads its body before any parsing, has no such
+/// restriction and hashes the literal bytes via `auth::hash_payload`.
+pub async fn hash_file_as_hawk_payload(path: &Path, content_type: &str) -> io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = PayloadHasher::new(content_type);
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Re-opens `path` as a byte stream suitable for `storage::put_object_streaming`.
+pub async fn stream_file(
+    path: &Path,
+) -> io::Result<impl rocket::futures::Stream<Item = Result<bytes::Bytes, io::Error>>> {
+    let file = tokio::fs::File::open(path).await?;
+    Ok(ReaderStream::new(file).map_err(|e| io::Error::new(io::ErrorKind::Other, e)))
+}